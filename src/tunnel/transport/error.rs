@@ -0,0 +1,97 @@
+use std::io;
+use std::io::ErrorKind;
+
+/// Distinguishes the ways a tunnel transport (websocket or HTTP/2) can fail, so callers such as
+/// the connection-pool/reconnect layer can decide whether to retry instead of treating every
+/// failure as an opaque I/O error.
+#[derive(Debug, thiserror::Error)]
+pub enum TunnelTransportError {
+    #[error("failed to establish the tunnel transport: {0}")]
+    Handshake(#[source] anyhow::Error),
+
+    #[error("no pong received within the keepalive deadline")]
+    PongTimeout,
+
+    #[error("peer closed the tunnel (code {code}): {reason}")]
+    PeerClosed { code: u16, reason: String },
+
+    #[error("peer violated the tunnel protocol: {0}")]
+    ProtocolViolation(String),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl From<TunnelTransportError> for io::Error {
+    fn from(err: TunnelTransportError) -> Self {
+        let kind = match &err {
+            TunnelTransportError::Io(io_err) => io_err.kind(),
+            TunnelTransportError::PongTimeout => ErrorKind::BrokenPipe,
+            TunnelTransportError::PeerClosed { .. } => ErrorKind::NotConnected,
+            TunnelTransportError::Handshake(_) => ErrorKind::ConnectionAborted,
+            TunnelTransportError::ProtocolViolation(_) => ErrorKind::InvalidData,
+        };
+
+        // Box `err` itself rather than its `Display` text, so a caller that only has an
+        // `io::Error` can still recover the structured variant via
+        // `err.into_inner().and_then(|e| e.downcast::<TunnelTransportError>().ok())`.
+        io::Error::new(kind, err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn downcast(err: io::Error) -> TunnelTransportError {
+        *err.into_inner().unwrap().downcast::<TunnelTransportError>().unwrap()
+    }
+
+    #[test]
+    fn test_handshake_maps_to_connection_aborted() {
+        let err: io::Error = TunnelTransportError::Handshake(anyhow::anyhow!("boom")).into();
+        assert_eq!(err.kind(), ErrorKind::ConnectionAborted);
+        assert!(matches!(downcast(err), TunnelTransportError::Handshake(_)));
+    }
+
+    #[test]
+    fn test_pong_timeout_maps_to_broken_pipe() {
+        let err: io::Error = TunnelTransportError::PongTimeout.into();
+        assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+        assert!(matches!(downcast(err), TunnelTransportError::PongTimeout));
+    }
+
+    #[test]
+    fn test_peer_closed_maps_to_not_connected() {
+        let err: io::Error = TunnelTransportError::PeerClosed {
+            code: 1000,
+            reason: "bye".to_string(),
+        }
+        .into();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+        match downcast(err) {
+            TunnelTransportError::PeerClosed { code, reason } => {
+                assert_eq!(code, 1000);
+                assert_eq!(reason, "bye");
+            }
+            other => panic!("unexpected variant: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_protocol_violation_maps_to_invalid_data() {
+        let err: io::Error = TunnelTransportError::ProtocolViolation("bad frame".to_string()).into();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        match downcast(err) {
+            TunnelTransportError::ProtocolViolation(msg) => assert_eq!(msg, "bad frame"),
+            other => panic!("unexpected variant: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_io_preserves_the_original_error_kind() {
+        let err: io::Error = TunnelTransportError::Io(io::Error::new(ErrorKind::TimedOut, "slow")).into();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(matches!(downcast(err), TunnelTransportError::Io(inner) if inner.kind() == ErrorKind::TimedOut));
+    }
+}
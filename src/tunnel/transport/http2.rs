@@ -0,0 +1,311 @@
+use crate::tunnel::transport::error::TunnelTransportError;
+use crate::tunnel::transport::{TunnelRead, TunnelWrite, MAX_PACKET_LENGTH};
+use crate::tunnel::{tunnel_to_jwt_token, RemoteAddr, JWT_HEADER_PREFIX};
+// `transport` lives on `WsClientConfig`, in the client config module.
+use crate::WsClientConfig;
+use anyhow::{anyhow, Context};
+use bytes::{Bytes, BytesMut};
+use h2::{RecvStream, SendStream};
+use hyper::header::{AUTHORIZATION, HOST, SEC_WEBSOCKET_PROTOCOL};
+use hyper::Request;
+use std::io;
+use std::io::ErrorKind;
+use std::ops::DerefMut;
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tracing::{debug, trace};
+use uuid::Uuid;
+
+/// Idle time before the HTTP/2 connection sends a PING frame to probe liveness, mirroring the
+/// application-level ping/pong keepalive used by the websocket transport.
+const H2_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(20);
+
+/// How long to wait for a PING ack before `h2` tears the connection down.
+const H2_KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Writes tunnel bytes as DATA frames on an HTTP/2 stream opened with Extended CONNECT
+/// (RFC 8441), as a drop-in alternative to the websocket transport for proxies/load balancers
+/// that only speak HTTP/2.
+pub struct Http2TunnelWrite {
+    send_stream: SendStream<Bytes>,
+    buf: BytesMut,
+}
+
+impl Http2TunnelWrite {
+    pub fn new(send_stream: SendStream<Bytes>) -> Self {
+        Self {
+            send_stream,
+            buf: BytesMut::with_capacity(MAX_PACKET_LENGTH),
+        }
+    }
+}
+
+impl TunnelWrite for Http2TunnelWrite {
+    fn buf_mut(&mut self) -> &mut BytesMut {
+        &mut self.buf
+    }
+
+    async fn write(&mut self) -> Result<(), io::Error> {
+        let chunk = self.buf.split().freeze();
+        if let Err(err) = self.send_stream.send_data(chunk, false) {
+            return Err(TunnelTransportError::Io(io::Error::new(ErrorKind::ConnectionAborted, err)).into());
+        }
+
+        Ok(())
+    }
+
+    async fn ping(&mut self) -> Result<(), io::Error> {
+        // Keepalive for this transport is connection-level, not per-stream: `connect` configures
+        // `keep_alive_interval`/`keep_alive_timeout` on the `h2::client::Builder`, so the
+        // connection driver task sends and tracks PING frames on its own. There's nothing to do
+        // per-tunnel here.
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<(), io::Error> {
+        if let Err(err) = self.send_stream.send_data(Bytes::new(), true) {
+            return Err(TunnelTransportError::Io(io::Error::new(ErrorKind::BrokenPipe, err)).into());
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Http2TunnelRead {
+    recv_stream: RecvStream,
+}
+
+impl Http2TunnelRead {
+    pub const fn new(recv_stream: RecvStream) -> Self {
+        Self { recv_stream }
+    }
+}
+
+impl TunnelRead for Http2TunnelRead {
+    async fn copy(&mut self, mut writer: impl AsyncWrite + Unpin + Send) -> Result<(), io::Error> {
+        let chunk = match self.recv_stream.data().await {
+            Some(Ok(chunk)) => chunk,
+            Some(Err(err)) => return Err(TunnelTransportError::Io(io::Error::new(ErrorKind::ConnectionAborted, err)).into()),
+            None => {
+                return Err(TunnelTransportError::Io(io::Error::new(ErrorKind::NotConnected, "http2 stream closed")).into())
+            }
+        };
+
+        trace!("receive http2 data frame of {} bytes", chunk.len());
+        let len = chunk.len();
+        let ret = writer.write_all(&chunk).await;
+        let _ = self.recv_stream.flow_control().release_capacity(len);
+
+        ret.map_err(|err| TunnelTransportError::Io(io::Error::new(ErrorKind::ConnectionAborted, err)).into())
+    }
+}
+
+/// Opens a tunnel over HTTP/2 using Extended CONNECT (RFC 8441) instead of the HTTP/1.1
+/// websocket upgrade used by [`super::websocket::connect`]. A single HTTP/2 connection can
+/// multiplex many of these tunnels as separate streams.
+pub async fn connect(
+    request_id: Uuid,
+    client_cfg: &WsClientConfig,
+    dest_addr: &RemoteAddr,
+) -> Result<(Http2TunnelRead, Http2TunnelWrite), TunnelTransportError> {
+    let mut pooled_cnx = client_cfg.cnx_pool().get().await.map_err(|err| {
+        TunnelTransportError::Handshake(anyhow!("failed to get a connection to the server from the pool: {err:?}"))
+    })?;
+
+    let transport = pooled_cnx.deref_mut().take().unwrap();
+    let (mut send_request, connection) = h2::client::Builder::new()
+        .enable_connect_protocol()
+        .keep_alive_interval(H2_KEEPALIVE_INTERVAL)
+        .keep_alive_timeout(H2_KEEPALIVE_TIMEOUT)
+        .handshake(transport)
+        .await
+        .with_context(|| format!("failed to do HTTP/2 handshake with the server {:?}", client_cfg.remote_addr))
+        .map_err(TunnelTransportError::Handshake)?;
+
+    tokio::spawn(async move {
+        if let Err(err) = connection.await {
+            debug!("HTTP/2 connection for request {request_id} terminated: {err:?}");
+        }
+    });
+
+    let mut req = Request::builder()
+        .method("CONNECT")
+        .uri(format!(
+            "https://{}/{}/events",
+            client_cfg.http_header_host, client_cfg.http_upgrade_path_prefix
+        ))
+        .header(HOST, &client_cfg.http_header_host)
+        .header(
+            SEC_WEBSOCKET_PROTOCOL,
+            format!("v1, {}{}", JWT_HEADER_PREFIX, tunnel_to_jwt_token(request_id, dest_addr)),
+        );
+
+    if let Some(auth) = &client_cfg.http_upgrade_credentials {
+        req = req.header(AUTHORIZATION, auth.clone());
+    }
+
+    let mut req = req
+        .body(())
+        .with_context(|| format!("failed to build HTTP/2 CONNECT request to {:?}", client_cfg.remote_addr))
+        .map_err(TunnelTransportError::Handshake)?;
+    req.extensions_mut().insert(h2::ext::Protocol::from_static("websocket"));
+
+    send_request
+        .ready()
+        .await
+        .with_context(|| format!("HTTP/2 connection to {:?} is not ready to send requests", client_cfg.remote_addr))
+        .map_err(TunnelTransportError::Handshake)?;
+    let (response, send_stream) = send_request
+        .send_request(req, false)
+        .with_context(|| format!("failed to send HTTP/2 CONNECT request to {:?}", client_cfg.remote_addr))
+        .map_err(TunnelTransportError::Handshake)?;
+
+    let response = response
+        .await
+        .with_context(|| format!("failed to get a response to the HTTP/2 CONNECT request to {:?}", client_cfg.remote_addr))
+        .map_err(TunnelTransportError::Handshake)?;
+    if response.status() != hyper::StatusCode::OK {
+        return Err(TunnelTransportError::Handshake(anyhow!(
+            "server {:?} rejected the HTTP/2 CONNECT tunnel with status {}",
+            client_cfg.remote_addr,
+            response.status()
+        )));
+    }
+
+    let recv_stream = response.into_body();
+
+    Ok((Http2TunnelRead::new(recv_stream), Http2TunnelWrite::new(send_stream)))
+}
+
+/// Which tunnel transport a client should use to reach the server, selected via
+/// `WsClientConfig::transport`. Defaults to the websocket upgrade, which is the transport every
+/// server deployed before HTTP/2 Extended CONNECT support understands.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TunnelTransportKind {
+    #[default]
+    WebSocket,
+    Http2,
+}
+
+/// Read half of whichever transport [`connect_with_transport`] picked, so callers don't need to
+/// be generic over [`super::websocket::WebsocketTunnelRead`] vs [`Http2TunnelRead`].
+pub enum AnyTunnelRead {
+    WebSocket(super::websocket::WebsocketTunnelRead),
+    Http2(Http2TunnelRead),
+}
+
+impl TunnelRead for AnyTunnelRead {
+    async fn copy(&mut self, writer: impl AsyncWrite + Unpin + Send) -> Result<(), io::Error> {
+        match self {
+            AnyTunnelRead::WebSocket(read) => read.copy(writer).await,
+            AnyTunnelRead::Http2(read) => read.copy(writer).await,
+        }
+    }
+}
+
+/// Write half of whichever transport [`connect_with_transport`] picked.
+pub enum AnyTunnelWrite {
+    WebSocket(super::websocket::WebsocketTunnelWrite),
+    Http2(Http2TunnelWrite),
+}
+
+impl TunnelWrite for AnyTunnelWrite {
+    fn buf_mut(&mut self) -> &mut BytesMut {
+        match self {
+            AnyTunnelWrite::WebSocket(write) => write.buf_mut(),
+            AnyTunnelWrite::Http2(write) => write.buf_mut(),
+        }
+    }
+
+    async fn write(&mut self) -> Result<(), io::Error> {
+        match self {
+            AnyTunnelWrite::WebSocket(write) => write.write().await,
+            AnyTunnelWrite::Http2(write) => write.write().await,
+        }
+    }
+
+    async fn ping(&mut self) -> Result<(), io::Error> {
+        match self {
+            AnyTunnelWrite::WebSocket(write) => write.ping().await,
+            AnyTunnelWrite::Http2(write) => write.ping().await,
+        }
+    }
+
+    async fn close(&mut self) -> Result<(), io::Error> {
+        match self {
+            AnyTunnelWrite::WebSocket(write) => write.close().await,
+            AnyTunnelWrite::Http2(write) => write.close().await,
+        }
+    }
+}
+
+/// Opens a tunnel using whichever transport `client_cfg.transport` selects, so the client entry
+/// point that used to call [`super::websocket::connect`] unconditionally can pick between it and
+/// [`connect`] (the HTTP/2 Extended CONNECT transport) without knowing about both.
+pub async fn connect_with_transport(
+    request_id: Uuid,
+    client_cfg: &WsClientConfig,
+    dest_addr: &RemoteAddr,
+) -> Result<(AnyTunnelRead, AnyTunnelWrite), TunnelTransportError> {
+    match client_cfg.transport {
+        TunnelTransportKind::WebSocket => {
+            let (read, write, _parts) = super::websocket::connect(request_id, client_cfg, dest_addr).await?;
+            Ok((AnyTunnelRead::WebSocket(read), AnyTunnelWrite::WebSocket(write)))
+        }
+        TunnelTransportKind::Http2 => {
+            let (read, write) = connect(request_id, client_cfg, dest_addr).await?;
+            Ok((AnyTunnelRead::Http2(read), AnyTunnelWrite::Http2(write)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::duplex;
+
+    #[test]
+    fn test_tunnel_transport_kind_default_is_websocket() {
+        assert_eq!(TunnelTransportKind::default(), TunnelTransportKind::WebSocket);
+    }
+
+    /// Drives a real, in-memory HTTP/2 client/server pair far enough to hand back genuine
+    /// `SendStream`/`RecvStream` values, so `AnyTunnelWrite`/`AnyTunnelRead` can be checked to
+    /// actually dispatch to the `Http2` variant's `write`/`copy` instead of just compiling.
+    #[tokio::test]
+    async fn test_any_tunnel_http2_variant_dispatches_to_the_stream() {
+        let (client_io, server_io) = duplex(64 * 1024);
+
+        let (mut h2_client, conn) = h2::client::handshake(client_io).await.unwrap();
+        tokio::spawn(async move {
+            let _ = conn.await;
+        });
+        let mut server_conn = h2::server::handshake(server_io).await.unwrap();
+
+        h2_client.ready().await.unwrap();
+        let request = hyper::Request::builder().method("GET").uri("/").body(()).unwrap();
+        let (response_fut, send_stream) = h2_client.send_request(request, false).unwrap();
+
+        let (req, mut send_response) = server_conn.accept().await.unwrap().unwrap();
+        // The server connection only reads and writes frames while it is polled, so keep driving
+        // it in the background or the response and the DATA frame below never get exchanged.
+        tokio::spawn(async move { while server_conn.accept().await.is_some() {} });
+        let response = hyper::Response::builder().status(200).body(()).unwrap();
+        send_response.send_response(response, false).unwrap();
+
+        let response = response_fut.await.unwrap();
+        // Keep the client's half of the response stream alive for the duration of the test; we
+        // only read from the server's half below.
+        let _client_recv_stream = response.into_body();
+        let server_recv_stream = req.into_body();
+
+        let mut any_write = AnyTunnelWrite::Http2(Http2TunnelWrite::new(send_stream));
+        any_write.buf_mut().extend_from_slice(b"hello");
+        any_write.write().await.unwrap();
+
+        let mut any_read = AnyTunnelRead::Http2(Http2TunnelRead::new(server_recv_stream));
+        let mut out = Vec::new();
+        any_read.copy(&mut out).await.unwrap();
+        assert_eq!(out, b"hello");
+    }
+}
@@ -1,45 +1,228 @@
+use crate::tunnel::transport::error::TunnelTransportError;
 use crate::tunnel::transport::{headers_from_file, TunnelRead, TunnelWrite, MAX_PACKET_LENGTH};
 use crate::tunnel::{tunnel_to_jwt_token, RemoteAddr, JWT_HEADER_PREFIX};
+// `websocket_permessage_deflate`, `websocket_ping_max_diff`, `websocket_ping_interval` and
+// `websocket_max_incoming_message_len` live on `WsClientConfig`, in the client config module.
 use crate::WsClientConfig;
 use anyhow::{anyhow, Context};
 use bytes::{Bytes, BytesMut};
 use fastwebsockets::{Frame, OpCode, Payload, WebSocketRead, WebSocketWrite};
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
 use futures_util::lock::Mutex;
 use http_body_util::Empty;
-use hyper::header::{AUTHORIZATION, SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION, UPGRADE};
+use hyper::header::{AUTHORIZATION, SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION, UPGRADE};
 use hyper::header::{CONNECTION, HOST, SEC_WEBSOCKET_KEY};
 use hyper::http::response::Parts;
 use hyper::upgrade::Upgraded;
 use hyper::Request;
 use hyper_util::rt::TokioExecutor;
 use hyper_util::rt::TokioIo;
+use std::collections::VecDeque;
 use std::io;
 use std::io::ErrorKind;
 use std::ops::DerefMut;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
 use tracing::{debug, trace};
 use uuid::Uuid;
 
+/// A message ends a DEFLATE block with this 4-byte sequence when flushed with `Z_SYNC_FLUSH`.
+/// Per RFC 7692 §7.2.1, the sender strips it before putting the payload on the wire and the
+/// receiver appends it back before inflating.
+const DEFLATE_TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// Parameters negotiated for the `permessage-deflate` extension (RFC 7692), derived from the
+/// server's echoed `Sec-WebSocket-Extensions` response header.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PermessageDeflateConfig {
+    pub client_no_context_takeover: bool,
+    pub server_no_context_takeover: bool,
+    pub server_max_window_bits: Option<u8>,
+}
+
+/// Parses the server's `Sec-WebSocket-Extensions` response header and returns the negotiated
+/// `permessage-deflate` parameters, or `None` if the server didn't accept the extension.
+fn parse_permessage_deflate(parts: &Parts) -> Option<PermessageDeflateConfig> {
+    let header = parts.headers.get(SEC_WEBSOCKET_EXTENSIONS)?;
+    let header = header.to_str().ok()?;
+
+    header.split(',').find_map(|extension| {
+        let mut params = extension.split(';').map(str::trim);
+        if params.next()? != "permessage-deflate" {
+            return None;
+        }
+
+        let mut cfg = PermessageDeflateConfig::default();
+        for param in params {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            match name.trim() {
+                "client_no_context_takeover" => cfg.client_no_context_takeover = true,
+                "server_no_context_takeover" => cfg.server_no_context_takeover = true,
+                "server_max_window_bits" => cfg.server_max_window_bits = value.trim().trim_matches('"').parse().ok(),
+                _ => {}
+            }
+        }
+
+        Some(cfg)
+    })
+}
+
+/// Deflates `input` with `Z_SYNC_FLUSH` and strips the trailing [`DEFLATE_TRAILER`], as required
+/// for a permessage-deflate message payload. Resets the compressor dictionary afterwards when
+/// context takeover is disabled.
+///
+/// `compress_vec`/`decompress_vec` only fill whatever spare capacity `output` already has, they
+/// never grow it themselves, so we have to keep calling them and reserving more room until all of
+/// `input` has actually been consumed.
+fn deflate_message(compress: &mut Compress, input: &[u8], no_context_takeover: bool) -> io::Result<Vec<u8>> {
+    let total_in_start = compress.total_in();
+    let mut output = Vec::with_capacity(input.len() + DEFLATE_TRAILER.len());
+
+    loop {
+        let consumed = (compress.total_in() - total_in_start) as usize;
+
+        compress
+            .compress_vec(&input[consumed..], &mut output, FlushCompress::Sync)
+            .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+
+        // The sync flush is only complete once zlib returns with spare room left in `output`; a
+        // completely filled buffer may still have flush output pending even with all input consumed.
+        let consumed = (compress.total_in() - total_in_start) as usize;
+        if consumed == input.len() && output.len() < output.capacity() {
+            break;
+        }
+
+        // The output buffer ran out of spare capacity before the flush could finish; grow it
+        // and feed the remaining input through again.
+        if output.len() == output.capacity() {
+            output.reserve(output.capacity().max(4096));
+        }
+    }
+
+    if output.ends_with(&DEFLATE_TRAILER) {
+        output.truncate(output.len() - DEFLATE_TRAILER.len());
+    }
+
+    if no_context_takeover {
+        compress.reset();
+    }
+
+    Ok(output)
+}
+
+/// Reverses [`deflate_message`]: appends the [`DEFLATE_TRAILER`] back and inflates. Resets the
+/// decompressor dictionary afterwards when context takeover is disabled.
+///
+/// As in `deflate_message`, `output` is only grown explicitly here, never by
+/// `decompress_vec` itself — a full `output` looks identical to "no more input to process"
+/// (`consumed == 0 && produced == 0`) unless we track how much of `input` we've actually
+/// consumed across calls and keep going until that reaches `input.len()`.
+///
+/// `max_output_len` bounds how large `output` is allowed to grow: the pre-inflate
+/// `msg.payload.len()` check in [`WebsocketTunnelRead::copy`] only bounds the *compressed*
+/// wire size, so without this a peer negotiating permessage-deflate could send a small,
+/// highly-compressible frame (a decompression bomb) and force an arbitrarily large
+/// allocation here. Exceeding the limit is a peer protocol violation, not a plain I/O error.
+fn inflate_message(
+    decompress: &mut Decompress,
+    payload: &[u8],
+    no_context_takeover: bool,
+    max_output_len: usize,
+) -> Result<Vec<u8>, TunnelTransportError> {
+    let mut input = Vec::with_capacity(payload.len() + DEFLATE_TRAILER.len());
+    input.extend_from_slice(payload);
+    input.extend_from_slice(&DEFLATE_TRAILER);
+
+    let total_in_start = decompress.total_in();
+    let mut output = Vec::with_capacity((payload.len() * 4).max(64).min(max_output_len + 1));
+
+    loop {
+        let consumed = (decompress.total_in() - total_in_start) as usize;
+        let total_out_before = decompress.total_out();
+
+        let status = decompress
+            .decompress_vec(&input[consumed..], &mut output, FlushDecompress::Sync)
+            .map_err(|err| TunnelTransportError::Io(io::Error::new(ErrorKind::InvalidData, err)))?;
+
+        if output.len() > max_output_len {
+            return Err(TunnelTransportError::ProtocolViolation(format!(
+                "decompressed message of at least {} bytes exceeds the {} byte limit",
+                output.len(),
+                max_output_len
+            )));
+        }
+
+        let produced = decompress.total_out() - total_out_before;
+        let consumed = (decompress.total_in() - total_in_start) as usize;
+        if status == Status::StreamEnd || (consumed == input.len() && produced == 0) {
+            break;
+        }
+
+        // The output buffer ran out of spare capacity before all of `input` was consumed;
+        // grow it and keep decompressing the remainder.
+        if output.len() == output.capacity() {
+            output.reserve(output.capacity().max(4096));
+        }
+    }
+
+    if no_context_takeover {
+        decompress.reset(false);
+    }
+
+    Ok(output)
+}
+
+/// Number of in-flight pings we keep send-timestamps for, to match against returning pongs.
+const MAX_OUTSTANDING_PINGS: usize = 16;
+
+/// Byte length of a ping/pong payload carrying a sequence number plus a microsecond timestamp.
+const PING_PAYLOAD_LEN: usize = 1 + 8;
+
 #[derive(Debug)]
 pub struct PingState {
     ping_seq: u8,
     pong_seq: u8,
     max_diff: u8,
+    ping_interval: Duration,
+    process_start: Instant,
+    outstanding: VecDeque<(u8, Instant)>,
+    srtt: Option<Duration>,
 }
 
 impl PingState {
-    pub const fn new() -> Self {
+    pub fn new(max_diff: u8, ping_interval: Duration) -> Self {
         Self {
             ping_seq: 0,
             pong_seq: 0,
-            // TODO: make this configurable
-            max_diff: 3,
+            max_diff,
+            ping_interval,
+            process_start: Instant::now(),
+            outstanding: VecDeque::with_capacity(MAX_OUTSTANDING_PINGS),
+            srtt: None,
         }
     }
 
+    /// How long the keepalive loop driving this tunnel should wait between sending pings, as
+    /// configured via `WsClientConfig::websocket_ping_interval`.
+    pub fn ping_interval(&self) -> Duration {
+        self.ping_interval
+    }
+
     fn is_ok(&self) -> bool {
-        self.ping_seq - self.pong_seq <= self.max_diff
+        if self.ping_seq - self.pong_seq > self.max_diff {
+            return false;
+        }
+
+        // Also bail out if the oldest outstanding ping has been waiting far longer than the
+        // smoothed RTT, even if we haven't yet accumulated `max_diff` worth of unanswered pings.
+        if let (Some(srtt), Some((_, sent_at))) = (self.srtt, self.outstanding.front()) {
+            if sent_at.elapsed() > (srtt * 4).max(Duration::from_secs(1)) {
+                return false;
+            }
+        }
+
+        true
     }
 
     fn ping_inc(&mut self) {
@@ -50,7 +233,35 @@ impl PingState {
         }
     }
 
-    fn set_pong_seq(&mut self, seq: u8) {
+    /// Builds the payload for the next ping frame: the sequence number followed by a monotonic
+    /// timestamp (microseconds since this `PingState` was created), and records the send instant
+    /// so the round-trip time can be computed once the matching pong comes back.
+    fn next_ping_payload(&mut self) -> [u8; PING_PAYLOAD_LEN] {
+        self.ping_inc();
+
+        let now = Instant::now();
+        if self.outstanding.len() == MAX_OUTSTANDING_PINGS {
+            self.outstanding.pop_front();
+        }
+        self.outstanding.push_back((self.ping_seq, now));
+
+        let micros = now.duration_since(self.process_start).as_micros() as u64;
+        let mut payload = [0u8; PING_PAYLOAD_LEN];
+        payload[0] = self.ping_seq;
+        payload[1..].copy_from_slice(&micros.to_be_bytes());
+        payload
+    }
+
+    /// Handles a received pong payload: advances `pong_seq` and, if the payload carries a
+    /// timestamp (or we still have the send instant on hand), folds a new round-trip sample into
+    /// the smoothed RTT estimate. A payload containing only the sequence byte is accepted for
+    /// backward compatibility, it just can't produce an RTT sample unless we still have the
+    /// matching entry in `outstanding`.
+    fn set_pong_seq(&mut self, payload: &[u8]) {
+        let Some(&seq) = payload.first() else {
+            return;
+        };
+
         if seq > self.pong_seq && seq <= self.ping_seq {
             self.pong_seq = seq;
         }
@@ -61,11 +272,43 @@ impl PingState {
         if self.ping_seq == self.pong_seq && self.ping_seq > u8::MAX / 2 {
             self.reset();
         }
+
+        if let Some(pos) = self.outstanding.iter().position(|(s, _)| *s == seq) {
+            let (_, sent_at) = self.outstanding.remove(pos).unwrap();
+            self.record_rtt_sample(sent_at.elapsed());
+        } else if payload.len() >= PING_PAYLOAD_LEN {
+            let sent_micros = u64::from_be_bytes(payload[1..PING_PAYLOAD_LEN].try_into().unwrap());
+            let now_micros = Instant::now().duration_since(self.process_start).as_micros() as u64;
+            if let Some(elapsed) = now_micros.checked_sub(sent_micros) {
+                self.record_rtt_sample(Duration::from_micros(elapsed));
+            }
+        }
+    }
+
+    fn record_rtt_sample(&mut self, sample: Duration) {
+        self.srtt = Some(match self.srtt {
+            Some(srtt) => (srtt * 7 + sample) / 8,
+            None => sample,
+        });
+    }
+
+    /// Smoothed round-trip time (EWMA, alpha = 1/8), once at least one pong has been matched.
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    /// Number of pings sent that have not yet been acknowledged by a matching pong, counting only
+    /// the `MAX_OUTSTANDING_PINGS` most recent ones; a sequence reset forgets all of them.
+    pub fn outstanding_pings(&self) -> usize {
+        self.outstanding.len()
     }
 
     fn reset(&mut self) {
         self.ping_seq = 0;
         self.pong_seq = 0;
+        // A ping sent before the wrap could otherwise collide with a new ping reusing the same
+        // post-reset sequence number, matching an RTT sample against the wrong send instant.
+        self.outstanding.clear();
     }
 }
 
@@ -73,17 +316,20 @@ pub struct WebsocketTunnelWrite {
     inner: Arc<Mutex<WebSocketWrite<WriteHalf<TokioIo<Upgraded>>>>>,
     buf: BytesMut,
     ping_state: Arc<Mutex<PingState>>,
+    deflate: Option<(Compress, bool)>,
 }
 
 impl WebsocketTunnelWrite {
     pub fn new(
         ws: Arc<Mutex<WebSocketWrite<WriteHalf<TokioIo<Upgraded>>>>>,
         ping_state: Arc<Mutex<PingState>>,
+        deflate: Option<PermessageDeflateConfig>,
     ) -> Self {
         Self {
             inner: ws,
             buf: BytesMut::with_capacity(MAX_PACKET_LENGTH),
             ping_state,
+            deflate: deflate.map(|cfg| (Compress::new(Compression::default(), false), cfg.client_no_context_takeover)),
         }
     }
 }
@@ -97,15 +343,21 @@ impl TunnelWrite for WebsocketTunnelWrite {
         let read_len = self.buf.len();
         let buf = &mut self.buf;
 
-        let ret = self
-            .inner
-            .lock()
-            .await
-            .write_frame(Frame::binary(Payload::BorrowedMut(&mut buf[..read_len])))
-            .await;
+        let ret = if let Some((compress, no_context_takeover)) = self.deflate.as_mut() {
+            let compressed = deflate_message(compress, &buf[..read_len], *no_context_takeover)?;
+            let mut frame = Frame::binary(Payload::Owned(compressed));
+            frame.rsv1 = true;
+            self.inner.lock().await.write_frame(frame).await
+        } else {
+            self.inner
+                .lock()
+                .await
+                .write_frame(Frame::binary(Payload::BorrowedMut(&mut buf[..read_len])))
+                .await
+        };
 
         if let Err(err) = ret {
-            return Err(io::Error::new(ErrorKind::ConnectionAborted, err));
+            return Err(TunnelTransportError::Io(io::Error::new(ErrorKind::ConnectionAborted, err)).into());
         }
 
         // If the buffer has been completely filled with previous read, Grows it !
@@ -133,23 +385,18 @@ impl TunnelWrite for WebsocketTunnelWrite {
         let mut ping_state = self.ping_state.lock().await;
         debug!("{:?}", *ping_state);
         if !ping_state.is_ok() {
-            return Err(io::Error::new(ErrorKind::BrokenPipe, "No pong received"));
+            return Err(TunnelTransportError::PongTimeout.into());
         }
-        ping_state.ping_inc();
-        debug!("Sending ping({})", ping_state.ping_seq);
+        let mut payload = ping_state.next_ping_payload();
+        debug!("Sending ping({}) srtt={:?}", payload[0], ping_state.srtt());
         if let Err(err) = self
             .inner
             .lock()
             .await
-            .write_frame(Frame::new(
-                true,
-                OpCode::Ping,
-                None,
-                Payload::BorrowedMut(&mut [ping_state.ping_seq]),
-            ))
+            .write_frame(Frame::new(true, OpCode::Ping, None, Payload::BorrowedMut(&mut payload)))
             .await
         {
-            return Err(io::Error::new(ErrorKind::BrokenPipe, err));
+            return Err(TunnelTransportError::Io(io::Error::new(ErrorKind::BrokenPipe, err)).into());
         }
 
         Ok(())
@@ -157,7 +404,7 @@ impl TunnelWrite for WebsocketTunnelWrite {
 
     async fn close(&mut self) -> Result<(), io::Error> {
         if let Err(err) = self.inner.lock().await.write_frame(Frame::close(1000, &[])).await {
-            return Err(io::Error::new(ErrorKind::BrokenPipe, err));
+            return Err(TunnelTransportError::Io(io::Error::new(ErrorKind::BrokenPipe, err)).into());
         }
 
         Ok(())
@@ -168,22 +415,41 @@ pub struct WebsocketTunnelRead {
     ws_rx: WebSocketRead<ReadHalf<TokioIo<Upgraded>>>,
     ws_tx: Arc<Mutex<WebSocketWrite<WriteHalf<TokioIo<Upgraded>>>>>,
     ping_state: Arc<Mutex<PingState>>,
+    deflate: Option<(Decompress, bool)>,
+    max_incoming_message_len: usize,
 }
 
 impl WebsocketTunnelRead {
-    pub const fn new(
+    pub fn new(
         ws_rx: WebSocketRead<ReadHalf<TokioIo<Upgraded>>>,
         ws_tx: Arc<Mutex<WebSocketWrite<WriteHalf<TokioIo<Upgraded>>>>>,
         ping_state: Arc<Mutex<PingState>>,
+        deflate: Option<PermessageDeflateConfig>,
+        max_incoming_message_len: usize,
     ) -> Self {
         Self {
             ws_rx,
             ws_tx,
             ping_state,
+            deflate: deflate.map(|cfg| (Decompress::new(false), cfg.server_no_context_takeover)),
+            max_incoming_message_len,
         }
     }
 }
 
+/// Parses a Close frame payload into its status code and UTF-8 reason, per RFC 6455 §5.5.1.
+/// Defaults to code 1005 (no status code present) for an empty payload, and lossily converts a
+/// reason that isn't valid UTF-8 instead of failing to parse the close altogether.
+fn parse_close_payload(payload: &[u8]) -> (u16, String) {
+    if payload.len() < 2 {
+        return (1005, String::new());
+    }
+
+    let code = u16::from_be_bytes([payload[0], payload[1]]);
+    let reason = String::from_utf8_lossy(&payload[2..]).into_owned();
+    (code, reason)
+}
+
 impl TunnelRead for WebsocketTunnelRead {
     async fn copy(&mut self, mut writer: impl AsyncWrite + Unpin + Send) -> Result<(), io::Error> {
         loop {
@@ -193,26 +459,60 @@ impl TunnelRead for WebsocketTunnelRead {
                 .await
             {
                 Ok(msg) => msg,
-                Err(err) => return Err(io::Error::new(ErrorKind::ConnectionAborted, err)),
+                Err(err) => return Err(TunnelTransportError::Io(io::Error::new(ErrorKind::ConnectionAborted, err)).into()),
             };
 
             trace!("receive ws frame {:?} {:?}", msg.opcode, msg.payload);
             match msg.opcode {
                 OpCode::Continuation | OpCode::Text | OpCode::Binary => {
-                    return match writer.write_all(msg.payload.as_ref()).await {
+                    if msg.payload.len() > self.max_incoming_message_len {
+                        let _ = self
+                            .ws_tx
+                            .lock()
+                            .await
+                            .write_frame(Frame::close(1009, b"message too big"))
+                            .await;
+                        return Err(TunnelTransportError::ProtocolViolation(format!(
+                            "incoming message of {} bytes exceeds the {} byte limit",
+                            msg.payload.len(),
+                            self.max_incoming_message_len
+                        ))
+                        .into());
+                    }
+
+                    let payload = if msg.rsv1 {
+                        let (decompress, no_context_takeover) = self.deflate.as_mut().ok_or_else(|| {
+                            TunnelTransportError::ProtocolViolation(
+                                "received a compressed frame but permessage-deflate was not negotiated".to_string(),
+                            )
+                        })?;
+                        match inflate_message(decompress, msg.payload.as_ref(), *no_context_takeover, self.max_incoming_message_len) {
+                            Ok(payload) => payload,
+                            Err(err @ TunnelTransportError::ProtocolViolation(_)) => {
+                                let _ = self.ws_tx.lock().await.write_frame(Frame::close(1009, b"message too big")).await;
+                                return Err(err.into());
+                            }
+                            Err(err) => return Err(err.into()),
+                        }
+                    } else {
+                        msg.payload.as_ref().to_vec()
+                    };
+
+                    return match writer.write_all(&payload).await {
                         Ok(_) => Ok(()),
-                        Err(err) => Err(io::Error::new(ErrorKind::ConnectionAborted, err)),
+                        Err(err) => Err(TunnelTransportError::Io(io::Error::new(ErrorKind::ConnectionAborted, err)).into()),
                     }
                 }
-                OpCode::Close => return Err(io::Error::new(ErrorKind::NotConnected, "websocket close")),
+                OpCode::Close => {
+                    let (code, reason) = parse_close_payload(msg.payload.as_ref());
+                    return Err(TunnelTransportError::PeerClosed { code, reason }.into());
+                }
                 // Pings get handled internally, see the closure that we pass to read_frame above
                 OpCode::Ping => continue,
                 OpCode::Pong => {
-                    let seq = msg.payload[0];
-                    debug!("Received pong({})", seq);
                     let mut ping_state = self.ping_state.lock().await;
-                    ping_state.set_pong_seq(seq);
-                    debug!("{:?}", *ping_state);
+                    ping_state.set_pong_seq(msg.payload.as_ref());
+                    debug!("Received pong, srtt={:?}", ping_state.srtt());
                 }
             };
         }
@@ -223,11 +523,10 @@ pub async fn connect(
     request_id: Uuid,
     client_cfg: &WsClientConfig,
     dest_addr: &RemoteAddr,
-) -> anyhow::Result<(WebsocketTunnelRead, WebsocketTunnelWrite, Parts)> {
-    let mut pooled_cnx = match client_cfg.cnx_pool().get().await {
-        Ok(cnx) => Ok(cnx),
-        Err(err) => Err(anyhow!("failed to get a connection to the server from the pool: {err:?}")),
-    }?;
+) -> Result<(WebsocketTunnelRead, WebsocketTunnelWrite, Parts), TunnelTransportError> {
+    let mut pooled_cnx = client_cfg.cnx_pool().get().await.map_err(|err| {
+        TunnelTransportError::Handshake(anyhow!("failed to get a connection to the server from the pool: {err:?}"))
+    })?;
 
     let mut req = Request::builder()
         .method("GET")
@@ -243,6 +542,10 @@ pub async fn connect(
         )
         .version(hyper::Version::HTTP_11);
 
+    if client_cfg.websocket_permessage_deflate {
+        req = req.header(SEC_WEBSOCKET_EXTENSIONS, "permessage-deflate; client_no_context_takeover");
+    }
+
     let headers = req.headers_mut().unwrap();
     for (k, v) in &client_cfg.http_headers {
         let _ = headers.remove(k);
@@ -266,28 +569,61 @@ pub async fn connect(
         }
     }
 
-    let req = req.body(Empty::<Bytes>::new()).with_context(|| {
-        format!(
-            "failed to build HTTP request to contact the server {:?}",
-            client_cfg.remote_addr
-        )
-    })?;
+    let req = req
+        .body(Empty::<Bytes>::new())
+        .with_context(|| {
+            format!(
+                "failed to build HTTP request to contact the server {:?}",
+                client_cfg.remote_addr
+            )
+        })
+        .map_err(TunnelTransportError::Handshake)?;
     debug!("with HTTP upgrade request {:?}", req);
     let transport = pooled_cnx.deref_mut().take().unwrap();
     let (mut ws, response) = fastwebsockets::handshake::client(&TokioExecutor::new(), req, transport)
         .await
-        .with_context(|| format!("failed to do websocket handshake with the server {:?}", client_cfg.remote_addr))?;
+        .with_context(|| format!("failed to do websocket handshake with the server {:?}", client_cfg.remote_addr))
+        .map_err(TunnelTransportError::Handshake)?;
 
     ws.set_auto_apply_mask(client_cfg.websocket_mask_frame);
+    // Bound how much memory fastwebsockets' own frame reassembly will allocate for a single
+    // message, so a hostile peer can't force an unbounded allocation before our own
+    // `max_incoming_message_len` check in `copy` ever gets a chance to look at the result.
+    //
+    // This has to stay strictly *larger* than `max_incoming_message_len`: fastwebsockets enforces
+    // its own cap inside `read_frame` by erroring the read outright (no close handshake), before
+    // `copy` ever sees the message. If the two caps were equal, that abrupt error would always
+    // fire first and the graceful `Close(1009, "message too big")` path below would never be
+    // reached for an oversized, uncompressed message.
+    ws.set_max_message_size(client_cfg.websocket_max_incoming_message_len.saturating_add(1));
+
+    let (parts, _) = response.into_parts();
+    let deflate = if client_cfg.websocket_permessage_deflate {
+        parse_permessage_deflate(&parts)
+    } else {
+        None
+    };
+    if client_cfg.websocket_permessage_deflate && deflate.is_none() {
+        debug!("server did not accept permessage-deflate, falling back to uncompressed frames");
+    }
 
     let (ws_rx, ws_tx) = ws.split(tokio::io::split);
     let ws_tx = Arc::new(Mutex::new(ws_tx));
-    let ping_state = Arc::new(Mutex::new(PingState::new()));
+    let ping_state = Arc::new(Mutex::new(PingState::new(
+        client_cfg.websocket_ping_max_diff,
+        client_cfg.websocket_ping_interval,
+    )));
 
     Ok((
-        WebsocketTunnelRead::new(ws_rx, ws_tx.clone(), ping_state.clone()),
-        WebsocketTunnelWrite::new(ws_tx, ping_state),
-        response.into_parts().0,
+        WebsocketTunnelRead::new(
+            ws_rx,
+            ws_tx.clone(),
+            ping_state.clone(),
+            deflate,
+            client_cfg.websocket_max_incoming_message_len,
+        ),
+        WebsocketTunnelWrite::new(ws_tx, ping_state, deflate),
+        parts,
     ))
 }
 
@@ -295,9 +631,131 @@ pub async fn connect(
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_deflate_inflate_round_trip_highly_compressible() {
+        let payload = vec![b'a'; 64 * 1024];
+        let mut compress = Compress::new(Compression::default(), false);
+        let mut decompress = Decompress::new(false);
+
+        let compressed = deflate_message(&mut compress, &payload, false).unwrap();
+        assert!(
+            compressed.len() < payload.len() / 4,
+            "expected more than 4x compression, got {} -> {} bytes",
+            payload.len(),
+            compressed.len()
+        );
+
+        let decompressed = inflate_message(&mut decompress, &compressed, false, payload.len()).unwrap();
+        assert_eq!(decompressed, payload);
+    }
+
+    #[test]
+    fn test_deflate_inflate_round_trip_incompressible() {
+        // A small xorshift PRNG keeps the test self-contained while avoiding the kind of
+        // repetition that deflate would shrink well, i.e. what exercised the truncation bug.
+        let mut state: u32 = 0x1234_5678;
+        let payload: Vec<u8> = (0..32 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state & 0xff) as u8
+            })
+            .collect();
+
+        let mut compress = Compress::new(Compression::default(), false);
+        let mut decompress = Decompress::new(false);
+
+        let compressed = deflate_message(&mut compress, &payload, false).unwrap();
+        let decompressed = inflate_message(&mut decompress, &compressed, false, payload.len()).unwrap();
+        assert_eq!(decompressed, payload);
+    }
+
+    #[test]
+    fn test_deflate_inflate_context_takeover_reset() {
+        let mut compress = Compress::new(Compression::default(), false);
+        let mut decompress = Decompress::new(false);
+
+        let first = deflate_message(&mut compress, b"hello hello hello", true).unwrap();
+        let second = deflate_message(&mut compress, b"hello hello hello", true).unwrap();
+        assert_eq!(first, second, "with no_context_takeover, every message compresses independently");
+
+        assert_eq!(
+            inflate_message(&mut decompress, &first, true, "hello hello hello".len()).unwrap(),
+            b"hello hello hello"
+        );
+        assert_eq!(
+            inflate_message(&mut decompress, &second, true, "hello hello hello".len()).unwrap(),
+            b"hello hello hello"
+        );
+    }
+
+    #[test]
+    fn test_inflate_message_rejects_decompression_bomb() {
+        // A highly-compressible payload whose inflated size exceeds the configured limit must be
+        // rejected instead of allocating an unbounded `output` buffer.
+        let payload = vec![0u8; 64 * 1024];
+        let mut compress = Compress::new(Compression::default(), false);
+        let mut decompress = Decompress::new(false);
+
+        let compressed = deflate_message(&mut compress, &payload, false).unwrap();
+        let err = inflate_message(&mut decompress, &compressed, false, 1024).unwrap_err();
+        assert!(matches!(err, TunnelTransportError::ProtocolViolation(_)));
+    }
+
+    #[test]
+    fn test_parse_permessage_deflate_absent() {
+        let (parts, _) = hyper::Response::new(()).into_parts();
+        assert!(parse_permessage_deflate(&parts).is_none());
+    }
+
+    #[test]
+    fn test_parse_permessage_deflate_present() {
+        let mut response = hyper::Response::new(());
+        response.headers_mut().insert(
+            SEC_WEBSOCKET_EXTENSIONS,
+            "permessage-deflate; server_no_context_takeover; server_max_window_bits=12"
+                .parse()
+                .unwrap(),
+        );
+        let (parts, _) = response.into_parts();
+
+        let cfg = parse_permessage_deflate(&parts).expect("extension should be recognized");
+        assert!(!cfg.client_no_context_takeover);
+        assert!(cfg.server_no_context_takeover);
+        assert_eq!(cfg.server_max_window_bits, Some(12));
+    }
+
+    #[test]
+    fn test_parse_close_payload_empty() {
+        // RFC 6455 §7.1.5: an empty Close payload means "no status code was present".
+        assert_eq!(parse_close_payload(&[]), (1005, String::new()));
+    }
+
+    #[test]
+    fn test_parse_close_payload_code_only() {
+        assert_eq!(parse_close_payload(&1000u16.to_be_bytes()), (1000, String::new()));
+    }
+
+    #[test]
+    fn test_parse_close_payload_code_and_reason() {
+        let mut payload = 1001u16.to_be_bytes().to_vec();
+        payload.extend_from_slice(b"going away");
+        assert_eq!(parse_close_payload(&payload), (1001, "going away".to_string()));
+    }
+
+    #[test]
+    fn test_parse_close_payload_non_utf8_reason_is_lossy() {
+        let mut payload = 1000u16.to_be_bytes().to_vec();
+        payload.extend_from_slice(&[0xff, 0xfe]);
+        let (code, reason) = parse_close_payload(&payload);
+        assert_eq!(code, 1000);
+        assert_eq!(reason, "\u{fffd}\u{fffd}");
+    }
+
     #[test]
     fn test_ping_state() {
-        let mut ping_state = PingState::new();
+        let mut ping_state = PingState::new(3, Duration::from_secs(20));
 
         // An initial ping state has zeroes and is OK
         assert!(ping_state.is_ok());
@@ -319,9 +777,9 @@ mod tests {
         assert!(!ping_state.is_ok());
 
         // We received two pongs, the pin state is OK again
-        ping_state.set_pong_seq(1);
+        ping_state.set_pong_seq(&[1]);
         assert!(ping_state.is_ok());
-        ping_state.set_pong_seq(4);
+        ping_state.set_pong_seq(&[4]);
         assert!(ping_state.is_ok());
 
         // Advance the ping state beyond the middle of the u8 range,
@@ -334,7 +792,7 @@ mod tests {
         assert!(!ping_state.is_ok());
 
         // As soon as we do receive a pong, we wrap the sequence numbers around
-        ping_state.set_pong_seq(130);
+        ping_state.set_pong_seq(&[130]);
         assert_eq!(ping_state.ping_seq, 0);
         assert_eq!(ping_state.pong_seq, 0);
         assert!(ping_state.is_ok());
@@ -342,10 +800,62 @@ mod tests {
         // If we receive pongs for every ping, we wrap at 128, half of the u8 range
         for it in 1..=128 {
             ping_state.ping_inc();
-            ping_state.set_pong_seq(it)
+            ping_state.set_pong_seq(&[it])
         }
         assert_eq!(ping_state.ping_seq, 0);
         assert_eq!(ping_state.pong_seq, 0);
         assert!(ping_state.is_ok());
     }
+
+    #[test]
+    fn test_ping_interval_returns_configured_value() {
+        let ping_state = PingState::new(3, Duration::from_secs(7));
+        assert_eq!(ping_state.ping_interval(), Duration::from_secs(7));
+    }
+
+    #[test]
+    fn test_set_pong_seq_records_rtt_sample_from_outstanding_entry() {
+        let mut ping_state = PingState::new(3, Duration::from_secs(20));
+        assert!(ping_state.srtt().is_none());
+
+        let payload = ping_state.next_ping_payload();
+        assert!(ping_state.srtt().is_none());
+
+        ping_state.set_pong_seq(&payload);
+        let srtt = ping_state.srtt().expect("a matching outstanding entry should produce an RTT sample");
+        assert!(srtt < Duration::from_secs(1), "round trip to self should be fast, got {srtt:?}");
+        assert_eq!(ping_state.outstanding_pings(), 0);
+    }
+
+    #[test]
+    fn test_set_pong_seq_falls_back_to_payload_timestamp() {
+        let mut ping_state = PingState::new(3, Duration::from_secs(20));
+
+        // Build a pong payload carrying a timestamp without going through `next_ping_payload`,
+        // so there is no matching entry in `outstanding` to remove.
+        ping_state.ping_seq = 1;
+        let sent_micros = Instant::now().duration_since(ping_state.process_start).as_micros() as u64;
+        let mut payload = [0u8; PING_PAYLOAD_LEN];
+        payload[0] = 1;
+        payload[1..].copy_from_slice(&sent_micros.to_be_bytes());
+
+        assert!(ping_state.outstanding.is_empty());
+        ping_state.set_pong_seq(&payload);
+        assert!(
+            ping_state.srtt().is_some(),
+            "a timestamp-bearing payload should still produce an RTT sample without an outstanding entry"
+        );
+    }
+
+    #[test]
+    fn test_is_ok_bails_out_when_oldest_outstanding_ping_exceeds_deadline() {
+        let mut ping_state = PingState::new(16, Duration::from_secs(20));
+        ping_state.srtt = Some(Duration::from_millis(50));
+        ping_state.outstanding.push_back((1, Instant::now() - Duration::from_secs(5)));
+
+        // `ping_seq - pong_seq` is well within `max_diff`, so only the deadline bail-out in
+        // `is_ok` (oldest outstanding ping waiting far longer than `(srtt * 4).max(1s)`) can be
+        // responsible for this returning false.
+        assert!(!ping_state.is_ok());
+    }
 }